@@ -1,10 +1,15 @@
-use std::collections::hash_map::Entry;
+use std::collections::hash_map::{DefaultHasher, Entry};
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs;
+use std::hash::{Hash, Hasher};
 use std::io;
+use std::mem;
 use std::path::Path;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
 
 use csv;
+use rayon::ThreadPoolBuilder;
 use regex::Regex;
 
 use config::{Config, Delimiter};
@@ -12,6 +17,11 @@ use select::SelectColumns;
 use util::{self, FilenameTemplate};
 use CliResult;
 
+/// Number of records handed to a worker thread at a time when running
+/// with `--jobs`. Batching keeps channel overhead low without forcing us
+/// to buffer the whole input in memory.
+const BATCH_SIZE: usize = 4096;
+
 static USAGE: &'static str = "
 Partitions the given CSV data into chunks based on the value of a column
 
@@ -34,6 +44,14 @@ partition options:
     --drop                 Drop the partition column from results.
     --max-open-files <n>   Maximum number of files to keep open.
                            [default: 512]
+    -j, --jobs <n>         The number of worker threads to partition with.
+                           Each key is assigned to exactly one worker (by
+                           hashing the key), so workers never contend for
+                           the same output file and no merge pass is
+                           needed. --max-open-files applies per worker.
+                           When set to 1 (the default), partitioning runs
+                           sequentially on the main thread.
+                           [default: 1]
 
 Common options:
     -h, --help             Display this message
@@ -55,17 +73,18 @@ struct Args {
     flag_drop: bool,
     flag_no_headers: bool,
     flag_delimiter: Option<Delimiter>,
+    flag_jobs: usize,
 }
 
 pub fn run(argv: &[&str]) -> CliResult<()> {
     let args: Args = util::get_args(USAGE, argv)?;
     fs::create_dir_all(&args.arg_outdir)?;
 
-    // It would be nice to support efficient parallel partitions, but doing
-    // do would involve more complicated inter-thread communication, with
-    // multiple readers and writers, and some way of passing buffers
-    // between them.
-    args.sequential_partition()
+    if args.flag_jobs <= 1 {
+        args.sequential_partition()
+    } else {
+        args.parallel_partition()
+    }
 }
 
 impl Args {
@@ -96,7 +115,7 @@ impl Args {
 
         let seen_keys = HashSet::new();
         let mut writers = lru::LruCache::new(self.flag_max_open_files);
-        let gen = WriterGenerator::new(self.flag_filename.clone());
+        let gen = Arc::new(Mutex::new(WriterGenerator::new(self.flag_filename.clone())));
 
         let mut pool = WriterPool {
             seen_keys,
@@ -111,28 +130,193 @@ impl Args {
         let mut row = csv::ByteRecord::new();
 
         while rdr.read_byte_record(&mut row)? {
-            // Decide what file to put this in.
-            let column = &row[key_col];
-            let key = match self.flag_prefix_length {
-                // We exceed --prefix-length, so ignore the extra bytes.
-                Some(len) if len < column.len() => &column[0..len],
-                _ => &column[..],
-            };
-            let wtr = pool.writer(&mut writers, key)?;
-            if self.flag_drop {
-                wtr.write_record(row.iter().enumerate().filter_map(|(i, e)| {
-                    if i != key_col {
-                        Some(e)
-                    } else {
-                        None
+            write_partitioned_row(
+                &mut pool,
+                &mut writers,
+                &row,
+                key_col,
+                self.flag_drop,
+                self.flag_prefix_length,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// A parallel partition that shards rows across `--jobs` worker
+    /// threads by hashing each row's key, so each key is owned by exactly
+    /// one worker and no merge pass is needed. `rconfig.reader()` isn't
+    /// `Send` (it boxes a `dyn Read`), so it must stay on the calling
+    /// thread; workers run on the rayon pool and never touch it. The
+    /// `WriterGenerator` is shared across workers so filename
+    /// sanitization stays collision-free even though sharding is keyed
+    /// on the raw, unsanitized value.
+    fn parallel_partition(&self) -> CliResult<()> {
+        let jobs = self.flag_jobs;
+        let rconfig = self.rconfig();
+        let mut rdr = rconfig.reader()?;
+        let headers = rdr.byte_headers()?.clone();
+        let key_col = self.key_column(&rconfig, &headers)?;
+
+        // Without a panic handler rayon aborts the process when a spawned
+        // job panics; with one, the panicking worker is reported below.
+        let pool = match ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .panic_handler(|_| {})
+            .build()
+        {
+            Ok(pool) => pool,
+            Err(e) => return fail!(format!("cannot start {} worker threads: {}", jobs, e)),
+        };
+
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..jobs)
+            .map(|_| mpsc::channel::<Vec<csv::ByteRecord>>())
+            .unzip();
+        let (done_txs, done_rxs): (Vec<_>, Vec<_>) =
+            (0..jobs).map(|_| mpsc::channel::<io::Result<()>>()).unzip();
+        let gen = Arc::new(Mutex::new(WriterGenerator::new(self.flag_filename.clone())));
+
+        for (receiver, done_tx) in receivers.into_iter().zip(done_txs) {
+            let rconfig = rconfig.clone();
+            let headers = headers.clone();
+            let outdir = self.arg_outdir.clone();
+            let gen = gen.clone();
+            let flag_drop = self.flag_drop;
+            let flag_prefix_length = self.flag_prefix_length;
+            let flag_max_open_files = self.flag_max_open_files;
+
+            pool.spawn(move || {
+                let mut writers = lru::LruCache::new(flag_max_open_files);
+                let mut worker_pool = WriterPool {
+                    seen_keys: HashSet::new(),
+                    gen,
+                    rconfig,
+                    outdir,
+                    flag_drop,
+                    headers,
+                    key_col,
+                };
+
+                let mut result = Ok(());
+                'batches: for batch in receiver.iter() {
+                    for row in &batch {
+                        result = write_partitioned_row(
+                            &mut worker_pool,
+                            &mut writers,
+                            row,
+                            key_col,
+                            flag_drop,
+                            flag_prefix_length,
+                        );
+                        if result.is_err() {
+                            break 'batches;
+                        }
                     }
-                }))?;
-            } else {
-                wtr.write_byte_record(&row)?;
+                }
+                let _ = done_tx.send(result);
+            });
+        }
+
+        // Read on the calling thread and fan rows out to their worker in
+        // batches, to keep channel traffic low.
+        let mut row = csv::ByteRecord::new();
+        let mut batches: Vec<Vec<csv::ByteRecord>> =
+            (0..jobs).map(|_| Vec::with_capacity(BATCH_SIZE)).collect();
+        let mut read_error = None;
+
+        loop {
+            match rdr.read_byte_record(&mut row) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => {
+                    read_error = Some(e);
+                    break;
+                }
+            }
+            let shard = shard_for(&row, key_col, self.flag_prefix_length) % jobs;
+            batches[shard].push(row.clone());
+            if batches[shard].len() >= BATCH_SIZE {
+                let batch = mem::replace(&mut batches[shard], Vec::with_capacity(BATCH_SIZE));
+                let _ = senders[shard].send(batch);
             }
         }
-        Ok(())
+        for (shard, batch) in batches.into_iter().enumerate() {
+            if !batch.is_empty() {
+                let _ = senders[shard].send(batch);
+            }
+        }
+        // Lets each worker's `receiver.iter()` end once it drains.
+        drop(senders);
+
+        let mut worker_error = None;
+        for done_rx in done_rxs {
+            match done_rx.recv() {
+                Ok(Err(e)) => {
+                    worker_error.get_or_insert(e);
+                }
+                // The worker panicked (or was killed) without reporting,
+                // e.g. from a poisoned `gen` lock after another worker
+                // panicked while holding it.
+                Err(_) => {
+                    worker_error.get_or_insert(io::Error::new(
+                        io::ErrorKind::Other,
+                        "a partition worker thread terminated without reporting a result",
+                    ));
+                }
+                Ok(Ok(())) => {}
+            }
+        }
+
+        if let Some(e) = read_error {
+            return Err(e.into());
+        }
+        match worker_error {
+            Some(e) => Err(e.into()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Select the (possibly prefix-truncated) partition key for `row`.
+fn partition_key(row: &csv::ByteRecord, key_col: usize, prefix_length: Option<usize>) -> &[u8] {
+    let column = &row[key_col];
+    match prefix_length {
+        // We exceed --prefix-length, so ignore the extra bytes.
+        Some(len) if len < column.len() => &column[0..len],
+        _ => &column[..],
+    }
+}
+
+/// Hash `row`'s partition key to pick the worker that owns it. Used to
+/// shard rows deterministically across `--jobs` threads.
+fn shard_for(row: &csv::ByteRecord, key_col: usize, prefix_length: Option<usize>) -> usize {
+    let key = partition_key(row, key_col, prefix_length);
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish() as usize
+}
+
+/// Write `row` to the file for its partition key, creating or reusing a
+/// writer from `writers` as needed.
+fn write_partitioned_row(
+    pool: &mut WriterPool,
+    writers: &mut lru::LruCache<Vec<u8>, BoxedWriter>,
+    row: &csv::ByteRecord,
+    key_col: usize,
+    flag_drop: bool,
+    prefix_length: Option<usize>,
+) -> io::Result<()> {
+    let key = partition_key(row, key_col, prefix_length);
+    let wtr = pool.writer(writers, key)?;
+    if flag_drop {
+        wtr.write_record(
+            row.iter()
+                .enumerate()
+                .filter_map(|(i, e)| if i != key_col { Some(e) } else { None }),
+        )?;
+    } else {
+        wtr.write_byte_record(row)?;
     }
+    Ok(())
 }
 
 type BoxedWriter = csv::Writer<Box<io::Write + 'static>>;
@@ -211,7 +395,7 @@ impl WriterGenerator {
 struct WriterPool {
     seen_keys: HashSet<Vec<u8>>,
     outdir: String,
-    gen: WriterGenerator,
+    gen: Arc<Mutex<WriterGenerator>>,
     rconfig: Config,
     flag_drop: bool,
     headers: csv::ByteRecord,
@@ -222,11 +406,11 @@ impl WriterPool {
     fn new_writer(&mut self, key: &[u8]) -> io::Result<BoxedWriter> {
         if self.seen_keys.contains(key) {
             // We have seen this file before; just re-open it.
-            self.gen.re_open(&*self.outdir, key)
+            self.gen.lock().unwrap().re_open(&*self.outdir, key)
         } else {
             self.seen_keys.insert(key.to_vec());
             // Need a new writer
-            let mut wtr = self.gen.writer(&*self.outdir, key)?;
+            let mut wtr = self.gen.lock().unwrap().writer(&*self.outdir, key)?;
             if !self.rconfig.no_headers {
                 if self.flag_drop {
                     wtr.write_record(self.headers.iter().enumerate().filter_map(|(i, e)| {